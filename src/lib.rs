@@ -1,85 +1,426 @@
 use core::cell::UnsafeCell;
 use core::fmt::{Debug, Display};
+use core::future::Future;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
-use std::thread::{Thread, ThreadId};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-type Condition<T> = Box<dyn Fn(&T) -> bool + Send>;
+mod sync;
+use sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use sync::mutex::{Mutex as RawMutex, MutexGuard as RawMutexGuard};
+use sync::thread::{self, Thread, ThreadId};
 
-type LockSlot<'a, T> = (
-    AtomicBool,
-    UnsafeCell<Option<parking_lot::MutexGuard<'a, T>>>,
-);
+pub(crate) type Condition<T> = Box<dyn Fn(&T) -> bool + Send>;
 
-type CondMap<T> = HashMap<ThreadId, (Thread, Condition<T>, usize)>;
+// A waiter is parked either as a blocked OS thread (`lock_when`) or as a
+// woken-up `Waker` (`lock_when_async`); `release` doesn't need to know which.
+// Shared with `rwlock` since a waiter there is parked the same way.
+pub(crate) enum Waiter {
+    Thread(Thread),
+    Async(Waker),
+}
+
+impl Waiter {
+    pub(crate) fn wake(&self) {
+        match self {
+            Waiter::Thread(thread) => thread.unpark(),
+            Waiter::Async(waker) => waker.wake_by_ref(),
+        }
+    }
+}
+
+// Waiters are served in registration (ticket) order. `tickets` is a
+// `ThreadId -> ticket` side index for O(log n) cancellation. `usize` is the
+// waiter's `Slot` address, type-erased since `CondMap` is shared between
+// `rwlock`'s two guard kinds.
+pub(crate) struct CondMap<T> {
+    pub(crate) queue: BTreeMap<u64, (ThreadId, Waiter, Condition<T>, usize)>,
+    pub(crate) tickets: HashMap<ThreadId, u64>,
+}
+
+// Not `#[derive(Default)]`: that would add a spurious `T: Default` bound,
+// since the derive can't see that `BTreeMap`/`HashMap` don't need one.
+impl<T> Default for CondMap<T> {
+    fn default() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            tickets: HashMap::new(),
+        }
+    }
+}
+
+impl<T> CondMap<T> {
+    pub(crate) fn insert(
+        &mut self,
+        ticket: u64,
+        id: ThreadId,
+        waiter: Waiter,
+        condition: Condition<T>,
+        addr: usize,
+    ) {
+        self.queue.insert(ticket, (id, waiter, condition, addr));
+        self.tickets.insert(id, ticket);
+    }
+}
+
+pub(crate) fn boxed_condition<'a, T>(f: impl Fn(&T) -> bool + Send + 'a) -> Condition<T> {
+    let f: Box<dyn Fn(&T) -> bool + Send + 'a> = Box::new(f);
+    unsafe { core::mem::transmute(f) }
+}
+
+// A queued `lock_when`/`lock_when_async` waiter, `Arc`-owned rather than
+// found through a type-erased stack address like `rwlock`'s `Slot`, since a
+// future has no stack frame to borrow. `guard` is a boxed
+// `RawMutexGuard<'_, Inner<T>>` erased to `*mut ()`: naming its real
+// lifetime here would force `T: 'static` everywhere.
+struct WaiterNode<T> {
+    done: AtomicBool,
+    guard: UnsafeCell<*mut ()>,
+    _marker: core::marker::PhantomData<fn(&T)>,
+}
+
+impl<T> Default for WaiterNode<T> {
+    fn default() -> Self {
+        Self {
+            done: AtomicBool::new(false),
+            guard: UnsafeCell::new(core::ptr::null_mut()),
+            _marker: core::marker::PhantomData,
+        }
+    }
+}
+
+// SAFETY: `guard` is only ever touched behind `done`'s Release/Acquire
+// pair, so the writer (`release`) and reader (the woken waiter) never
+// touch the cell at the same time.
+unsafe impl<T> Sync for WaiterNode<T> {}
+
+// SAFETY: a matched waiter ends up dropping a `MutexGuard` locked by
+// whichever thread called `release`, i.e. on a different thread than it
+// was acquired on. Sound because `parking_lot`'s raw mutex has no
+// same-thread-unlock requirement; `release` already relied on this before
+// `WaiterNode` existed.
+unsafe impl<T> Send for WaiterNode<T> {}
+
+// Moves a live, still-locked guard onto the heap and erases its pointer so
+// it can be stashed in a `WaiterNode<T>`'s `*mut ()`. Paired exactly once
+// with `unbox_guard` by whichever side observes `WaiterNode::done`.
+fn box_guard<T>(guard: RawMutexGuard<'_, Inner<T>>) -> *mut () {
+    Box::into_raw(Box::new(guard)) as *mut ()
+}
+
+// SAFETY: `ptr` must come from `box_guard` for this same `T`, not yet
+// passed to `unbox_guard`. The caller's `'a` is always the real borrow of
+// `self: &'a Mutex<T>` it already holds.
+unsafe fn unbox_guard<'a, T>(ptr: *mut ()) -> RawMutexGuard<'a, Inner<T>> {
+    *unsafe { Box::from_raw(ptr as *mut RawMutexGuard<'a, Inner<T>>) }
+}
+
+type WaiterEntry<T> = (Waiter, Condition<T>, Arc<WaiterNode<T>>);
+
+// Waiters for a single `Mutex`, queued in the order they registered. Lives
+// inside `Inner` so both registering (`lock_when`) and releasing
+// (`Mutex::release`) reach it through the one lock that also guards `T`.
+//
+// Keyed only by ticket, not by `ThreadId`: `lock_when`'s blocking waiters do
+// get one OS thread each, but `lock_when_async`'s don't - an executor can
+// poll any number of pending futures from the same worker thread, so a
+// `ThreadId`-keyed side index would let one of them cancel or re-wake
+// another's entry by mistake. A ticket is already unique per registration
+// and every caller that needs to remove or update its own entry already has
+// the one it was handed, so there's no index to keep in sync at all.
+struct WaiterList<T> {
+    next_ticket: u64,
+    queue: BTreeMap<u64, WaiterEntry<T>>,
+}
+
+impl<T> Default for WaiterList<T> {
+    fn default() -> Self {
+        Self {
+            next_ticket: 0,
+            queue: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> WaiterList<T> {
+    fn next_ticket(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        ticket
+    }
+
+    fn insert(
+        &mut self,
+        ticket: u64,
+        waiter: Waiter,
+        condition: Condition<T>,
+        node: Arc<WaiterNode<T>>,
+    ) {
+        self.queue.insert(ticket, (waiter, condition, node));
+    }
+
+    // Returns whether an entry was actually removed, so callers can keep
+    // the `Mutex`'s waiter count in sync.
+    fn remove(&mut self, ticket: u64) -> bool {
+        self.queue.remove(&ticket).is_some()
+    }
+
+    fn update_waiter(&mut self, ticket: u64, waiter: Waiter) {
+        if let Some(entry) = self.queue.get_mut(&ticket) {
+            entry.0 = waiter;
+        }
+    }
+}
+
+struct Inner<T> {
+    data: T,
+    waiters: WaiterList<T>,
+}
 
-// TODO: can we get this down to one lock?
-pub struct Mutex<T>(parking_lot::Mutex<T>, parking_lot::Mutex<CondMap<T>>);
+pub struct Mutex<T> {
+    inner: RawMutex<Inner<T>>,
+    // Relaxed headcount of `inner.waiters`, checked before `release` bothers
+    // scanning the list at all: the common case is nobody waiting.
+    waiting: AtomicUsize,
+}
 
-pub struct MutexGuard<'a, 'b, T>(Option<parking_lot::MutexGuard<'b, T>>, &'a Mutex<T>);
+pub struct MutexGuard<'a, 'b, T>(Option<RawMutexGuard<'b, Inner<T>>>, &'a Mutex<T>);
 
 impl<T> Mutex<T> {
     pub fn new(t: T) -> Self {
-        Self(t.into(), Default::default())
+        Self {
+            inner: RawMutex::new(Inner {
+                data: t,
+                waiters: Default::default(),
+            }),
+            waiting: AtomicUsize::new(0),
+        }
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
-        let guard = self.0.lock();
-        MutexGuard(Some(guard), &self)
+    pub fn lock(&self) -> MutexGuard<'_, '_, T> {
+        let guard = self.inner.lock();
+        MutexGuard(Some(guard), self)
     }
 
-    pub fn lock_when<F: Fn(&T) -> bool + Send>(&self, condition: F) -> MutexGuard<T> {
-        let guard = self.0.lock();
-        if condition(guard.deref()) {
-            return MutexGuard(Some(guard), &self);
+    pub fn lock_when<F: Fn(&T) -> bool + Send>(&self, condition: F) -> MutexGuard<'_, '_, T> {
+        let mut inner = self.inner.lock();
+        if condition(&inner.data) {
+            return MutexGuard(Some(inner), self);
         }
-        drop(guard);
 
-        let thread = std::thread::current();
-        let id = thread.id();
-        fn boxed<'a, T>(f: impl Fn(&T) -> bool + Send + 'a) -> Box<dyn Fn(&T) -> bool + Send> {
-            let f: Box<dyn Fn(&T) -> bool + Send + 'a> = Box::new(f);
-            unsafe { core::mem::transmute(f) }
-        }
-        let condition: Condition<T> = boxed(condition);
-        let slot: LockSlot<T> = Default::default();
-        let addr = &slot as *const _ as usize;
-        let mut mapguard = self.1.lock();
-        mapguard.insert(id, (thread, condition, addr));
-        drop(mapguard);
+        let thread = thread::current();
+        let ticket = inner.waiters.next_ticket();
+        let condition: Condition<T> = boxed_condition(condition);
+        let node: Arc<WaiterNode<T>> = Default::default();
+        inner
+            .waiters
+            .insert(ticket, Waiter::Thread(thread), condition, Arc::clone(&node));
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        drop(inner);
 
         loop {
-            std::thread::park();
-            if slot.0.load(Ordering::Acquire) {
-                let guard = slot.1.into_inner().unwrap();
-                return MutexGuard(Some(guard), &self);
+            thread::park();
+            if node.done.load(Ordering::Acquire) {
+                // SAFETY: `done` was just observed true with Acquire, so
+                // the Release store in `release` that set it happens-before
+                // this read, and `release` hands a given node's pointer to
+                // exactly one waiter.
+                let guard = unsafe { unbox_guard(*node.guard.get()) };
+                return MutexGuard(Some(guard), self);
             }
         }
     }
 
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        self.0
+    pub fn lock_when_for<F: Fn(&T) -> bool + Send>(
+        &self,
+        timeout: Duration,
+        condition: F,
+    ) -> Option<MutexGuard<'_, '_, T>> {
+        self.lock_when_until(Instant::now() + timeout, condition)
+    }
+
+    pub fn lock_when_until<F: Fn(&T) -> bool + Send>(
+        &self,
+        deadline: Instant,
+        condition: F,
+    ) -> Option<MutexGuard<'_, '_, T>> {
+        let mut inner = self.inner.lock();
+        if condition(&inner.data) {
+            return Some(MutexGuard(Some(inner), self));
+        }
+
+        let thread = thread::current();
+        let ticket = inner.waiters.next_ticket();
+        let condition: Condition<T> = boxed_condition(condition);
+        let node: Arc<WaiterNode<T>> = Default::default();
+        inner
+            .waiters
+            .insert(ticket, Waiter::Thread(thread), condition, Arc::clone(&node));
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        drop(inner);
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            thread::park_timeout(remaining);
+            if node.done.load(Ordering::Acquire) {
+                let guard = unsafe { unbox_guard(*node.guard.get()) };
+                return Some(MutexGuard(Some(guard), self));
+            }
+        }
+
+        // Deadline expired: drop our registration, but `release` may have
+        // already taken it and handed off a guard right before we got here,
+        // so that guard must not be dropped silently.
+        self.cancel(ticket, &node)
+            .map(|guard| MutexGuard(Some(guard), self))
+    }
+
+    pub async fn lock_async(&self) -> MutexGuard<'_, '_, T> {
+        self.lock_when_async(|_| true).await
+    }
+
+    pub async fn lock_when_async<F: Fn(&T) -> bool + Send + Unpin>(
+        &self,
+        condition: F,
+    ) -> MutexGuard<'_, '_, T> {
+        LockWhenAsync {
+            mutex: self,
+            condition: Some(condition),
+            registered: None,
+        }
+        .await
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, '_, T>> {
+        self.inner
             .try_lock()
-            .map(|guard| MutexGuard(Some(guard), &self))
+            .map(|guard| MutexGuard(Some(guard), self))
     }
 
-    fn release(&self, guard: parking_lot::MutexGuard<T>) {
-        let mut mapguard = self.1.lock();
-        // TODO:  Would extract_if() be faster?  Does it visit the remaining entries when dropped?
+    fn release(&self, guard: RawMutexGuard<'_, Inner<T>>) {
+        // Fast path: nobody's waiting, so there's nothing to scan for.
+        if self.waiting.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let mut guard = guard;
+        let Inner { data, waiters } = &mut *guard;
+        // `queue` is keyed by ticket, so this scans waiters oldest-first.
         let mut rm = None;
-        for (k, v) in mapguard.iter() {
-            if v.1(guard.deref()) {
-                let slot: &LockSlot<T> = unsafe { &*(v.2 as *const LockSlot<T>) };
-                unsafe { *slot.1.get() = Some(guard) };
-                slot.0.store(true, Ordering::Release);
-                v.0.unpark();
-                rm = Some(k.clone());
+        for (ticket, (_, condition, _)) in waiters.queue.iter() {
+            if condition(data) {
+                rm = Some(*ticket);
                 break;
             }
         }
-        if let Some(ref k) = rm {
-            mapguard.remove(k);
+        let Some(ticket) = rm else {
+            return;
+        };
+        let (waiter, _, node) = waiters.queue.remove(&ticket).unwrap();
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        // SAFETY: see `box_guard`/`unbox_guard`. The guard and `done` must
+        // be published before `wake()`, or the waiter could observe
+        // `done == false`, park again, and never be woken a second time.
+        unsafe { *node.guard.get() = box_guard(guard) };
+        node.done.store(true, Ordering::Release);
+        waiter.wake();
+    }
+
+    // Drops a cancelled waiter's bookkeeping entry, called from both
+    // `lock_when_until`'s timeout path and `LockWhenAsync`'s `Drop`. Can't
+    // call `self.inner.lock()` unconditionally: if `release` already
+    // matched `node`, its boxed guard keeps `inner` locked until this very
+    // waiter unboxes it, so a blocking `lock()` here would self-deadlock.
+    // Spinning on `try_lock` alongside `node.done` avoids that.
+    fn cancel(&self, ticket: u64, node: &WaiterNode<T>) -> Option<RawMutexGuard<'_, Inner<T>>> {
+        loop {
+            if node.done.load(Ordering::Acquire) {
+                // SAFETY: see `box_guard`/`unbox_guard`.
+                return Some(unsafe { unbox_guard(*node.guard.get()) });
+            }
+            if let Some(mut inner) = self.inner.try_lock() {
+                if inner.waiters.remove(ticket) {
+                    self.waiting.fetch_sub(1, Ordering::Relaxed);
+                }
+                drop(inner);
+                return node
+                    .done
+                    .load(Ordering::Acquire)
+                    .then(|| unsafe { unbox_guard(*node.guard.get()) });
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+// Backing future for `lock_when_async`. Registration is keyed by a
+// `WaiterNode` owned jointly with `Inner::waiters`, so this future has no
+// field whose address is shared externally, and needs no pinning of its own.
+struct LockWhenAsync<'a, T, F> {
+    mutex: &'a Mutex<T>,
+    condition: Option<F>,
+    registered: Option<(u64, Arc<WaiterNode<T>>)>,
+}
+
+impl<'a, T, F: Fn(&T) -> bool + Send + Unpin> Future for LockWhenAsync<'a, T, F> {
+    type Output = MutexGuard<'a, 'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if this.registered.is_none() {
+            let mut inner = this.mutex.inner.lock();
+            let condition = this.condition.take().unwrap();
+            if condition(&inner.data) {
+                return Poll::Ready(MutexGuard(Some(inner), this.mutex));
+            }
+
+            let ticket = inner.waiters.next_ticket();
+            let condition: Condition<T> = boxed_condition(condition);
+            let node: Arc<WaiterNode<T>> = Default::default();
+            inner.waiters.insert(
+                ticket,
+                Waiter::Async(cx.waker().clone()),
+                condition,
+                Arc::clone(&node),
+            );
+            this.mutex.waiting.fetch_add(1, Ordering::Relaxed);
+            drop(inner);
+            this.registered = Some((ticket, node));
+            return Poll::Pending;
+        }
+
+        let (ticket, node) = this.registered.as_ref().unwrap();
+        if node.done.load(Ordering::Acquire) {
+            let guard = unsafe { unbox_guard(*node.guard.get()) };
+            this.registered = None;
+            return Poll::Ready(MutexGuard(Some(guard), this.mutex));
+        }
+
+        // The executor may re-poll us with a different waker than the one we
+        // registered (e.g. after being moved to another task); keep it fresh.
+        let mut inner = this.mutex.inner.lock();
+        inner
+            .waiters
+            .update_waiter(*ticket, Waiter::Async(cx.waker().clone()));
+        drop(inner);
+        Poll::Pending
+    }
+}
+
+impl<T, F> Drop for LockWhenAsync<'_, T, F> {
+    fn drop(&mut self) {
+        let Some((ticket, node)) = self.registered.take() else {
+            return;
+        };
+        // `release` may have already taken this entry and handed off a
+        // guard right before we got here; if so, the guard must not be
+        // dropped silently, so pass it on to the next waiter instead.
+        if let Some(guard) = self.mutex.cancel(ticket, &node) {
+            self.mutex.release(guard);
         }
     }
 }
@@ -110,13 +451,13 @@ impl<T: Debug> Debug for Mutex<T> {
 impl<T> Deref for MutexGuard<'_, '_, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        self.0.as_ref().unwrap().deref()
+        &self.0.as_ref().unwrap().data
     }
 }
 
 impl<T> DerefMut for MutexGuard<'_, '_, T> {
     fn deref_mut(&mut self) -> &mut T {
-        self.0.as_mut().unwrap().deref_mut()
+        &mut self.0.as_mut().unwrap().data
     }
 }
 
@@ -138,4 +479,7 @@ impl<T: Display> Display for MutexGuard<'_, '_, T> {
     }
 }
 
+mod rwlock;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
 mod tests;
@@ -0,0 +1,249 @@
+use core::cell::UnsafeCell;
+use core::fmt::{Debug, Display};
+use core::ops::{Deref, DerefMut};
+
+use crate::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::thread::{self, ThreadId};
+use crate::{boxed_condition, CondMap, Condition, Waiter};
+
+type Slot<G> = (AtomicBool, UnsafeCell<Option<G>>);
+
+// Same condition-gated hand-off as `Mutex`, parameterized over the guard
+// kind: `readers` queues `read_when` waiters (one `RwLockReadGuard` each),
+// `writers` queues `write_when` waiters (exclusive, one per release). Only a
+// writer can change `T`, so only `release_write` re-checks either queue.
+pub struct RwLock<T>(
+    parking_lot::RwLock<T>,
+    parking_lot::Mutex<CondMap<T>>,
+    parking_lot::Mutex<CondMap<T>>,
+    AtomicU64,
+);
+
+pub struct RwLockReadGuard<'a, 'b, T>(Option<parking_lot::RwLockReadGuard<'b, T>>, &'a RwLock<T>);
+
+pub struct RwLockWriteGuard<'a, 'b, T>(Option<parking_lot::RwLockWriteGuard<'b, T>>, &'a RwLock<T>);
+
+impl<T> RwLock<T> {
+    pub fn new(t: T) -> Self {
+        Self(
+            t.into(),
+            Default::default(),
+            Default::default(),
+            AtomicU64::new(0),
+        )
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, '_, T> {
+        let guard = self.0.read();
+        RwLockReadGuard(Some(guard), self)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, '_, T> {
+        let guard = self.0.write();
+        RwLockWriteGuard(Some(guard), self)
+    }
+
+    pub fn read_when<F: Fn(&T) -> bool + Send>(&self, condition: F) -> RwLockReadGuard<'_, '_, T> {
+        let guard = self.0.read();
+        if condition(guard.deref()) {
+            return RwLockReadGuard(Some(guard), self);
+        }
+        drop(guard);
+
+        let thread = thread::current();
+        let id = thread.id();
+        let ticket = self.3.fetch_add(1, Ordering::Relaxed);
+        let condition: Condition<T> = boxed_condition(condition);
+        let slot: Slot<parking_lot::RwLockReadGuard<'_, T>> = Default::default();
+        let addr = &slot as *const _ as usize;
+        let mut mapguard = self.1.lock();
+        mapguard.insert(ticket, id, Waiter::Thread(thread), condition, addr);
+        drop(mapguard);
+
+        loop {
+            thread::park();
+            if slot.0.load(Ordering::Acquire) {
+                let guard = slot.1.into_inner().unwrap();
+                return RwLockReadGuard(Some(guard), self);
+            }
+        }
+    }
+
+    pub fn write_when<F: Fn(&T) -> bool + Send>(
+        &self,
+        condition: F,
+    ) -> RwLockWriteGuard<'_, '_, T> {
+        let guard = self.0.write();
+        if condition(guard.deref()) {
+            return RwLockWriteGuard(Some(guard), self);
+        }
+        drop(guard);
+
+        let thread = thread::current();
+        let id = thread.id();
+        let ticket = self.3.fetch_add(1, Ordering::Relaxed);
+        let condition: Condition<T> = boxed_condition(condition);
+        let slot: Slot<parking_lot::RwLockWriteGuard<'_, T>> = Default::default();
+        let addr = &slot as *const _ as usize;
+        let mut mapguard = self.2.lock();
+        mapguard.insert(ticket, id, Waiter::Thread(thread), condition, addr);
+        drop(mapguard);
+
+        loop {
+            thread::park();
+            if slot.0.load(Ordering::Acquire) {
+                let guard = slot.1.into_inner().unwrap();
+                return RwLockWriteGuard(Some(guard), self);
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, '_, T>> {
+        self.0
+            .try_read()
+            .map(|guard| RwLockReadGuard(Some(guard), self))
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, '_, T>> {
+        self.0
+            .try_write()
+            .map(|guard| RwLockWriteGuard(Some(guard), self))
+    }
+
+    fn release_read(&self, guard: parking_lot::RwLockReadGuard<'_, T>) {
+        // `T` can't have changed (the guard is shared/read-only), so no
+        // queued `read_when`/`write_when` condition can newly be satisfied.
+        drop(guard);
+    }
+
+    fn release_write(&self, guard: parking_lot::RwLockWriteGuard<'_, T>) {
+        let mut guard = Some(guard);
+        let mut writers = self.2.lock();
+        let mut rm = None;
+        for (ticket, (id, waiter, condition, addr)) in writers.queue.iter() {
+            if condition(guard.as_deref().unwrap()) {
+                let slot: &Slot<parking_lot::RwLockWriteGuard<'_, T>> =
+                    unsafe { &*(*addr as *const Slot<parking_lot::RwLockWriteGuard<'_, T>>) };
+                unsafe { *slot.1.get() = guard.take() };
+                slot.0.store(true, Ordering::Release);
+                waiter.wake();
+                rm = Some((*ticket, *id));
+                break;
+            }
+        }
+        if let Some((ticket, id)) = rm {
+            writers.queue.remove(&ticket);
+            writers.tickets.remove(&id);
+            return;
+        }
+        drop(writers);
+
+        // No write_when waiter wanted this state; wake every read_when
+        // waiter it satisfies instead, each with its own freshly-acquired
+        // `RwLockReadGuard`.
+        drop(guard.take());
+        let mut readers = self.1.lock();
+        // Held across the scan and every hand-off below to block a writer
+        // from landing in between and invalidating an already-matched
+        // waiter's condition before it reacquires its own guard.
+        let test_guard = self.0.read();
+        let matched: Vec<(u64, ThreadId, usize)> = readers
+            .queue
+            .iter()
+            .filter(|(_, (_, _, condition, _))| condition(test_guard.deref()))
+            .map(|(ticket, (id, _, _, addr))| (*ticket, *id, *addr))
+            .collect();
+
+        for (ticket, id, addr) in matched {
+            let slot: &Slot<parking_lot::RwLockReadGuard<'_, T>> =
+                unsafe { &*(addr as *const Slot<parking_lot::RwLockReadGuard<'_, T>>) };
+            unsafe { *slot.1.get() = Some(self.0.read()) };
+            slot.0.store(true, Ordering::Release);
+            if let Some((_, waiter, _, _)) = readers.queue.get(&ticket) {
+                waiter.wake();
+            }
+            readers.queue.remove(&ticket);
+            readers.tickets.remove(&id);
+        }
+        drop(test_guard);
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> RwLock<T> {
+        RwLock::new(Default::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(t: T) -> Self {
+        RwLock::new(t)
+    }
+}
+
+impl<T: Debug> Debug for RwLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RwLock(")?;
+        match self.try_read() {
+            Some(guard) => Debug::fmt(guard.deref(), f)?,
+            None => f.write_str("<locked>")?,
+        }
+        f.write_str(")")
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, '_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.as_ref().unwrap().deref()
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, '_, T> {
+    fn drop(&mut self) {
+        self.1.release_read(self.0.take().unwrap())
+    }
+}
+
+impl<T: Debug> Debug for RwLockReadGuard<'_, '_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for RwLockReadGuard<'_, '_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, '_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.as_ref().unwrap().deref()
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, '_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.as_mut().unwrap().deref_mut()
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, '_, T> {
+    fn drop(&mut self) {
+        self.1.release_write(self.0.take().unwrap())
+    }
+}
+
+impl<T: Debug> Debug for RwLockWriteGuard<'_, '_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for RwLockWriteGuard<'_, '_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
@@ -0,0 +1,62 @@
+//! Swaps the atomics/thread-parking/mutex primitives `lib.rs` uses for
+//! loom's equivalents under `--cfg loom`, so the guard hand-off can be
+//! model-checked without `lib.rs` itself needing any `#[cfg(loom)]`.
+
+#[cfg(not(loom))]
+pub(crate) mod atomic {
+    pub(crate) use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+}
+
+#[cfg(loom)]
+pub(crate) mod atomic {
+    pub(crate) use loom::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+}
+
+#[cfg(not(loom))]
+pub(crate) mod thread {
+    pub(crate) use std::thread::{current, park, park_timeout, Thread, ThreadId};
+}
+
+#[cfg(not(loom))]
+pub(crate) mod mutex {
+    pub(crate) use parking_lot::{Mutex, MutexGuard};
+}
+
+// `parking_lot::Mutex` is invisible to loom's model checker, so a real lock
+// acquisition against it can deadlock a modeled run instead of being
+// explored. These wrappers adapt `loom::sync::Mutex`'s fallible, poisoning
+// API back to the infallible `lock()`/`try_lock()` calls the rest of the
+// crate makes.
+#[cfg(loom)]
+pub(crate) mod mutex {
+    pub(crate) struct Mutex<T>(loom::sync::Mutex<T>);
+    pub(crate) type MutexGuard<'a, T> = loom::sync::MutexGuard<'a, T>;
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(t: T) -> Self {
+            Self(loom::sync::Mutex::new(t))
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+            self.0.try_lock().ok()
+        }
+    }
+}
+
+#[cfg(loom)]
+pub(crate) mod thread {
+    pub(crate) use loom::thread::{current, park, Thread, ThreadId};
+
+    // loom models `park`/`unpark` but not timed waits, so under loom the
+    // timeout variants degrade to an untimed park; they aren't what the loom
+    // harness is exploring (see tests/loom.rs).
+    pub(crate) fn park_timeout(_timeout: std::time::Duration) {
+        park();
+    }
+}
@@ -1,6 +1,305 @@
 #![cfg(test)]
 
 use super::*;
+use std::task::Wake;
+
+// A `Waker` that just records whether it was woken, so a future can be
+// polled exactly when something has actually changed instead of spinning
+// blindly. No executor dependency: every future under test here only wakes
+// from inside this same process, so recording a flag is enough to drive it.
+struct FlagWaker(AtomicBool);
+
+impl Default for FlagWaker {
+    fn default() -> Self {
+        Self(AtomicBool::new(false))
+    }
+}
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+impl FlagWaker {
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Acquire)
+    }
+}
+
+// Drives `fut` to completion on the current thread, busy-polling between
+// wakeups. Only suitable for tests: a real executor would park instead of
+// spinning, but the futures under test here resolve in microseconds.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = core::pin::pin!(fut);
+    let flag: Arc<FlagWaker> = Default::default();
+    let waker = Waker::from(Arc::clone(&flag));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+        while !flag.take() {
+            std::thread::yield_now();
+        }
+    }
+}
+
+#[test]
+fn lock_async_resolves() {
+    let mutex = Mutex::new(5);
+    let guard = block_on(mutex.lock_async());
+    assert_eq!(*guard, 5);
+}
+
+#[test]
+fn lock_when_async_waits_for_condition() {
+    let mutex = Arc::new(Mutex::new(0));
+    let setter = {
+        let mutex = Arc::clone(&mutex);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            *mutex.lock() = 42;
+        })
+    };
+    let guard = block_on(mutex.lock_when_async(|v| *v == 42));
+    assert_eq!(*guard, 42);
+    setter.join().unwrap();
+}
+
+// Regression test for a deadlock the single-lock `Mutex` redesign
+// introduced: polling a `lock_when_async` future registers it as a waiter;
+// if the condition is then satisfied in-line by another thread's `release`
+// before the future is ever polled again, `release` hands that future's
+// `WaiterNode` a still-locked guard. Dropping the future at that point used
+// to call `self.mutex.inner.lock()` unconditionally to remove the (already
+// consumed) registration - a lock only this very drop could ever free,
+// since `release` hands off without unlocking. `Mutex::cancel`'s
+// `try_lock`/`done` spin fixes this; this test hangs forever if it regresses.
+#[test]
+fn dropping_lock_when_async_after_inline_handoff_does_not_deadlock() {
+    let mutex = Mutex::new(0);
+    // Boxed rather than `core::pin::pin!`-ed: the test needs to actually
+    // drop the future partway through, and dropping a `Pin<&mut F>` only
+    // drops the reference, not the pinned value it points to.
+    let mut fut = Box::pin(mutex.lock_when_async(|v| *v == 1));
+    let flag: Arc<FlagWaker> = Default::default();
+    let waker = Waker::from(Arc::clone(&flag));
+    let mut cx = Context::from_waker(&waker);
+    assert!(matches!(fut.as_mut().poll(&mut cx), Poll::Pending));
+
+    // Satisfies the condition and, via this guard's drop, runs `release` in
+    // line - handing our still-pending future's node the guard before we
+    // ever poll it again.
+    *mutex.lock() = 1;
+
+    drop(fut);
+
+    // The handed-off guard must have been forwarded rather than dropped
+    // silently, so the mutex is still usable and its value unchanged.
+    assert_eq!(*mutex.lock(), 1);
+}
+
+// Waiters are served in registration (ticket) order, not in whatever order
+// `release`'s scan happens to iterate a hash-based structure. Registers N
+// waiters, all on the same always-true-once-set condition, by polling each
+// exactly once in order - since registration happens synchronously inside
+// that first poll, this pins down ticket order precisely instead of relying
+// on OS thread scheduling to land waiters in a particular sequence.
+#[test]
+fn lock_when_async_serves_waiters_in_fifo_order() {
+    const N: usize = 5;
+
+    let mutex = Mutex::new(0usize);
+    let flags: [Arc<FlagWaker>; N] = core::array::from_fn(|_| Default::default());
+    let wakers: [Waker; N] = core::array::from_fn(|i| Waker::from(Arc::clone(&flags[i])));
+    let mut futs: [_; N] =
+        core::array::from_fn(|_| Box::pin(mutex.lock_when_async(|v: &usize| *v > 0)));
+
+    for (fut, waker) in futs.iter_mut().zip(&wakers) {
+        let mut cx = Context::from_waker(waker);
+        assert!(matches!(fut.as_mut().poll(&mut cx), Poll::Pending));
+    }
+
+    // Satisfies every waiter's condition at once; only the oldest ticket is
+    // actually handed a guard here, keeping the mutex locked until it's
+    // dropped below.
+    *mutex.lock() = 1;
+
+    let mut order = Vec::with_capacity(N);
+    for _ in 0..N {
+        let woken = flags
+            .iter()
+            .position(|f| f.take())
+            .expect("exactly one waiter is handed the guard per release");
+        order.push(woken);
+        let mut cx = Context::from_waker(&wakers[woken]);
+        let Poll::Ready(guard) = futs[woken].as_mut().poll(&mut cx) else {
+            panic!("woken waiter's condition is already satisfied");
+        };
+        // Dropping this guard is what lets `release` hand the next-oldest
+        // waiter its turn.
+        drop(guard);
+    }
+
+    assert_eq!(order, (0..N).collect::<Vec<_>>());
+}
+
+// Regression test for a bug in the old `ThreadId`-keyed waiter bookkeeping:
+// an executor can poll several `lock_when_async` futures pending on the same
+// worker thread, so two such futures used to collide on the same `ThreadId`
+// key. Dropping one (before its condition was ever satisfied) could then
+// remove the *other* one's queue entry instead, losing its wakeup entirely.
+// Registers two such futures from this one thread, drops the first, then
+// satisfies only the second's condition and checks it still resolves, and
+// that the mutex isn't left stuck locked by the abandoned first entry.
+#[test]
+fn lock_when_async_drop_does_not_disturb_other_same_thread_waiter() {
+    let mutex = Mutex::new(0);
+
+    let mut fut_a = Box::pin(mutex.lock_when_async(|v| *v == 1));
+    let flag_a: Arc<FlagWaker> = Default::default();
+    let waker_a = Waker::from(Arc::clone(&flag_a));
+    let mut cx_a = Context::from_waker(&waker_a);
+    assert!(matches!(fut_a.as_mut().poll(&mut cx_a), Poll::Pending));
+
+    let mut fut_b = Box::pin(mutex.lock_when_async(|v| *v == 2));
+    let flag_b: Arc<FlagWaker> = Default::default();
+    let waker_b = Waker::from(Arc::clone(&flag_b));
+    let mut cx_b = Context::from_waker(&waker_b);
+    assert!(matches!(fut_b.as_mut().poll(&mut cx_b), Poll::Pending));
+
+    // `fut_a`'s condition (`*v == 1`) is never satisfied, so this drops an
+    // abandoned entry rather than a matched one.
+    drop(fut_a);
+
+    *mutex.lock() = 2;
+    assert!(flag_b.take(), "second waiter was never woken");
+    let Poll::Ready(guard) = fut_b.as_mut().poll(&mut cx_b) else {
+        panic!("second waiter's condition is satisfied but it's still pending");
+    };
+    assert_eq!(*guard, 2);
+    drop(guard);
+
+    // The abandoned first entry must not have left the mutex stuck locked.
+    assert!(mutex.try_lock().is_some());
+}
+
+#[test]
+fn lock_when_for_succeeds_before_deadline() {
+    let mutex = Arc::new(Mutex::new(0));
+    let setter = {
+        let mutex = Arc::clone(&mutex);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            *mutex.lock() = 1;
+        })
+    };
+    let guard = mutex.lock_when_for(std::time::Duration::from_secs(5), |v| *v == 1);
+    assert_eq!(guard.map(|g| *g), Some(1));
+    setter.join().unwrap();
+}
+
+#[test]
+fn lock_when_for_times_out_and_mutex_stays_usable() {
+    let mutex = Mutex::new(0);
+    let result = mutex.lock_when_for(std::time::Duration::from_millis(20), |v| *v == 1);
+    assert!(result.is_none());
+
+    // The timed-out waiter's registration must have been cleaned up, and
+    // the mutex must still work normally afterward.
+    assert_eq!(*mutex.lock(), 0);
+    *mutex.lock() = 1;
+    assert_eq!(*mutex.lock_when(|v| *v == 1), 1);
+}
+
+// "The crux": a deadline expiring at (almost) the same instant as another
+// thread's `release` matches the same waiter's condition. Before
+// `Mutex::cancel`, `lock_when_until`'s timeout path unconditionally
+// relocked `inner` to remove its own registration; if `release` had
+// already matched it and handed off a guard in that exact window, that
+// relock deadlocked on a lock only this call could ever free. Run across
+// many deadlines straddling zero to land on both sides of the race.
+#[test]
+fn lock_when_until_crux_race_does_not_lose_guard_or_deadlock() {
+    let mutex = Arc::new(Mutex::new(0usize));
+    for i in 0..200u64 {
+        *mutex.lock() = 0;
+        let setter = {
+            let mutex = Arc::clone(&mutex);
+            std::thread::spawn(move || *mutex.lock() = 1)
+        };
+        let deadline = Instant::now() + std::time::Duration::from_micros(i % 50);
+        let result = mutex.lock_when_until(deadline, |v| *v == 1);
+        setter.join().unwrap();
+        if let Some(guard) = result {
+            assert_eq!(*guard, 1);
+        }
+    }
+
+    // No registration or guard was ever leaked: the mutex is still usable.
+    assert_eq!(*mutex.lock(), 1);
+}
+
+#[test]
+fn rwlock_read_and_write() {
+    let lock = RwLock::new(1);
+    assert_eq!(*lock.read(), 1);
+    *lock.write() += 1;
+    assert_eq!(*lock.read(), 2);
+}
+
+#[test]
+fn rwlock_write_when_blocks_until_condition_then_wakes() {
+    let lock = Arc::new(RwLock::new(0));
+    let setter = {
+        let lock = Arc::clone(&lock);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            *lock.write() = 1;
+        })
+    };
+    let mut guard = lock.write_when(|v| *v == 1);
+    *guard += 1;
+    drop(guard);
+    setter.join().unwrap();
+    assert_eq!(*lock.read(), 2);
+}
+
+#[test]
+fn rwlock_read_when_blocks_until_condition_then_wakes() {
+    let lock = Arc::new(RwLock::new(0));
+    let setter = {
+        let lock = Arc::clone(&lock);
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            *lock.write() = 7;
+        })
+    };
+    let guard = lock.read_when(|v| *v == 7);
+    assert_eq!(*guard, 7);
+    setter.join().unwrap();
+}
+
+#[test]
+fn rwlock_try_read_and_try_write_contention() {
+    let lock = RwLock::new(0);
+    let _r1 = lock.try_read().unwrap();
+    let _r2 = lock.try_read().unwrap();
+    assert!(lock.try_write().is_none());
+    drop(_r1);
+    drop(_r2);
+
+    let w = lock.try_write().unwrap();
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(w);
+
+    assert!(lock.try_read().is_some());
+}
 
 #[derive(Default)]
 struct PCQ<T>(Mutex<std::collections::VecDeque<T>>);
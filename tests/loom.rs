@@ -0,0 +1,48 @@
+//! Model-checks the unsafe guard hand-off in `lock_when`/`release`: a
+//! `MutexGuard` is boxed onto the heap, written through
+//! `*node.guard.get()`, and published across threads with an `AtomicBool`
+//! Release/Acquire pair (`WaiterNode::done`), plus a raw-pointer round-trip
+//! through `box_guard`/`unbox_guard`. Exhaustively explores interleavings of
+//! two producers and a waiter to check there's no lost wakeup and no
+//! use-after-free of the `Arc`-owned `WaiterNode`.
+//!
+//! Permanently unstable: only compiles with `--cfg loom`, which also pulls
+//! the `cfg(loom)`-gated `loom` dependency into the library, and is not part
+//! of the default `cargo test` run. The full
+//! interleaving space is large enough that it's run bounded:
+//!
+//!     RUSTFLAGS="--cfg loom" LOOM_MAX_PREEMPTIONS=2 cargo test --test loom --release
+
+#![cfg(loom)]
+
+use ccs_mutex::Mutex;
+use std::sync::Arc;
+
+#[test]
+fn lock_when_no_lost_wakeup() {
+    loom::model(|| {
+        let mutex = Arc::new(Mutex::new(0usize));
+
+        let producers: Vec<_> = (0..2)
+            .map(|_| {
+                let mutex = Arc::clone(&mutex);
+                loom::thread::spawn(move || {
+                    *mutex.lock() += 1;
+                })
+            })
+            .collect();
+
+        let waiter = {
+            let mutex = Arc::clone(&mutex);
+            loom::thread::spawn(move || {
+                let guard = mutex.lock_when(|total| *total == 2);
+                assert_eq!(*guard, 2);
+            })
+        };
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        waiter.join().unwrap();
+    });
+}